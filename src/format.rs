@@ -0,0 +1,110 @@
+//! Known ARZ header/version combinations.
+//!
+//! The 24-byte file header always carries an `id`/`version` pair; the
+//! combination identifies which on-disk layout the rest of the file uses
+//! (string-table encoding, whether record-table entries carry a file-time
+//! field, and which codec record data is compressed with). [`Format::detect`]
+//! maps a header's `id`/`version` to the variant that governs parsing.
+
+use crate::reader::Error;
+
+/// Record compression codec used by a [`Format`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Codec {
+    Zlib,
+    Lz4,
+}
+
+/// A known ARZ header/version combination.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// Titan Quest-style layout: `id` 0x02, `version` 0x02. Record table
+    /// entries have no trailing file-time field.
+    TitanQuest,
+    /// Classic Grim Dawn layout: `id` 0x02, `version` 0x03.
+    GrimDawn,
+    /// Grim Dawn Definitive Edition layout: `id` 0x02, `version` 0x04.
+    /// Records are lz4-compressed instead of zlib-compressed.
+    GrimDawnDefinitiveEdition,
+}
+
+impl Format {
+    /// Identifies the format from the `id`/`version` fields of the file header.
+    pub fn detect(id: u16, version: u16) -> Result<Format, Error> {
+        match (id, version) {
+            (0x02, 0x02) => Ok(Format::TitanQuest),
+            (0x02, 0x03) => Ok(Format::GrimDawn),
+            (0x02, 0x04) => Ok(Format::GrimDawnDefinitiveEdition),
+            (id, version) => Err(Error::UnsupportedFormat { id, version }),
+        }
+    }
+
+    /// Whether record-table entries in this format carry a trailing
+    /// `file_time` field.
+    pub fn has_file_time(self) -> bool {
+        !matches!(self, Format::TitanQuest)
+    }
+
+    /// Whether string-table entries carry an explicit `u32` length prefix.
+    /// `TitanQuest` predates the length-prefixed convention and stores
+    /// each string null-terminated instead.
+    pub fn has_length_prefixed_strings(self) -> bool {
+        !matches!(self, Format::TitanQuest)
+    }
+
+    /// Compression codec used for record data in this format.
+    pub fn codec(self) -> Codec {
+        match self {
+            Format::TitanQuest | Format::GrimDawn => Codec::Zlib,
+            Format::GrimDawnDefinitiveEdition => Codec::Lz4,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_titan_quest() {
+        assert_eq!(Format::detect(0x02, 0x02).unwrap(), Format::TitanQuest);
+    }
+
+    #[test]
+    fn detects_grim_dawn() {
+        assert_eq!(Format::detect(0x02, 0x03).unwrap(), Format::GrimDawn);
+    }
+
+    #[test]
+    fn detects_grim_dawn_definitive_edition() {
+        assert_eq!(
+            Format::detect(0x02, 0x04).unwrap(),
+            Format::GrimDawnDefinitiveEdition
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_id_version_with_observed_values() {
+        match Format::detect(0x09, 0x01) {
+            Err(Error::UnsupportedFormat { id: 0x09, version: 0x01 }) => {}
+            other => panic!("expected UnsupportedFormat {{ id: 9, version: 1 }}, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn only_titan_quest_omits_file_time_and_length_prefixed_strings() {
+        assert!(!Format::TitanQuest.has_file_time());
+        assert!(!Format::TitanQuest.has_length_prefixed_strings());
+        for format in [Format::GrimDawn, Format::GrimDawnDefinitiveEdition] {
+            assert!(format.has_file_time());
+            assert!(format.has_length_prefixed_strings());
+        }
+    }
+
+    #[test]
+    fn only_definitive_edition_uses_lz4() {
+        assert_eq!(Format::TitanQuest.codec(), Codec::Zlib);
+        assert_eq!(Format::GrimDawn.codec(), Codec::Zlib);
+        assert_eq!(Format::GrimDawnDefinitiveEdition.codec(), Codec::Lz4);
+    }
+}
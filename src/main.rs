@@ -1,30 +1,51 @@
+mod format;
 mod reader;
+mod record;
+mod verify;
 
 use reader::Reader;
+use record::{Field, FieldValue};
 
 use std::error;
 use std::fmt;
+use std::fs;
 use std::fs::File;
+use std::io::{Read, Seek};
+use std::path::Path;
 
-use clap::{App, Arg};
+use clap::{App, Arg, SubCommand};
+use glob::Pattern;
+use indicatif::{ProgressBar, ProgressStyle};
 
 pub enum Error {
     InvalidInput,
+    InvalidFilter,
     Io,
     InvalidHeader,
+    VerificationFailed,
+    UnsafeRecordPath(String),
 }
 
 static ERROR_INVALID_ARGUMENT: &str = "Missing argument for .arz file path! Cannot continue.";
-static ERROR_IO: &str = "Failed to open the given file for reading.";
+static ERROR_INVALID_FILTER: &str = "Invalid glob pattern passed to --filter.";
+static ERROR_IO: &str = "Failed to read the given file, or write to the output directory.";
 static ERROR_INVALID_HEADER: &str =
     "Invalid file header, cannot read the given file as an ARZ database!";
+static ERROR_VERIFICATION_FAILED: &str = "Database failed verification, see mismatches above.";
 
 impl fmt::Debug for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             Error::InvalidInput => write!(f, "{}", ERROR_INVALID_ARGUMENT),
+            Error::InvalidFilter => write!(f, "{}", ERROR_INVALID_FILTER),
             Error::Io => write!(f, "{}", ERROR_IO),
             Error::InvalidHeader => write!(f, "{}", ERROR_INVALID_HEADER),
+            Error::VerificationFailed => write!(f, "{}", ERROR_VERIFICATION_FAILED),
+            Error::UnsafeRecordPath(path) => write!(
+                f,
+                "record path '{}' escapes the output directory, refusing to extract it",
+                path
+            ),
         }
     }
 }
@@ -35,8 +56,15 @@ impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Error::InvalidInput => write!(f, "{}", ERROR_INVALID_ARGUMENT),
+            Error::InvalidFilter => write!(f, "{}", ERROR_INVALID_FILTER),
             Error::Io => write!(f, "{}", ERROR_IO),
             Error::InvalidHeader => write!(f, "{}", ERROR_INVALID_HEADER),
+            Error::VerificationFailed => write!(f, "{}", ERROR_VERIFICATION_FAILED),
+            Error::UnsafeRecordPath(path) => write!(
+                f,
+                "record path '{}' escapes the output directory, refusing to extract it",
+                path
+            ),
         }
     }
 }
@@ -48,13 +76,198 @@ fn main() -> Result<(), Error> {
         .author("evpobr <evpobr@gmail.com>")
         .arg(
             Arg::with_name("INPUT")
-                .help("Sets the input file to use")
+                .help("Sets the input .arz file to use")
                 .required(true)
                 .index(1),
         )
+        .subcommand(SubCommand::with_name("list").about("Lists the records in the database"))
+        .subcommand(
+            SubCommand::with_name("extract")
+                .about("Decompresses every record into OUT_DIR")
+                .arg(
+                    Arg::with_name("OUT_DIR")
+                        .help("Directory records are extracted into")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("filter")
+                        .long("filter")
+                        .value_name("GLOB")
+                        .takes_value(true)
+                        .help("Only extract paths matching this glob pattern"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("verify")
+                .about("Re-inflates every record and checks the database footer checksum"),
+        )
         .get_matches();
+
     let input = matches.value_of("INPUT").ok_or(Error::InvalidInput)?;
     let file = File::open(input).map_err(|_| Error::Io)?;
-    let _reader = Reader::new(file).map_err(|_| Error::InvalidHeader)?;
+    let mut reader = Reader::new(file).map_err(|_| Error::InvalidHeader)?;
+
+    match matches.subcommand() {
+        ("extract", Some(sub_matches)) => {
+            let out_dir = sub_matches.value_of("OUT_DIR").ok_or(Error::InvalidInput)?;
+            let filter = sub_matches
+                .value_of("filter")
+                .map(Pattern::new)
+                .transpose()
+                .map_err(|_| Error::InvalidFilter)?;
+            extract(&mut reader, Path::new(out_dir), filter.as_ref())
+        }
+        ("verify", Some(_)) => verify(&mut reader),
+        _ => list(&mut reader),
+    }
+}
+
+fn verify<R: Read + Seek>(reader: &mut Reader<R>) -> Result<(), Error> {
+    let report = reader.verify().map_err(|_| Error::Io)?;
+    for (path, error) in &report.mismatches {
+        if path.is_empty() {
+            println!("footer: {}", error);
+        } else {
+            println!("{}: {}", path, error);
+        }
+    }
+    println!(
+        "{} record(s) checked, {} mismatch(es)",
+        report.records_checked,
+        report.mismatches.len()
+    );
+
+    if report.is_ok() {
+        Ok(())
+    } else {
+        Err(Error::VerificationFailed)
+    }
+}
+
+fn list<R: Read + Seek>(reader: &mut Reader<R>) -> Result<(), Error> {
+    for entry in reader.iter().map_err(|_| Error::Io)? {
+        let entry = entry.map_err(|_| Error::Io)?;
+        println!(
+            "{}\t{}\t{}\t{}",
+            entry.path, entry.record_type, entry.compressed_size, entry.uncompressed_size
+        );
+    }
     Ok(())
 }
+
+fn extract<R: Read + Seek>(
+    reader: &mut Reader<R>,
+    out_dir: &Path,
+    filter: Option<&Pattern>,
+) -> Result<(), Error> {
+    let bar = ProgressBar::new(reader.record_table_entry_count() as u64);
+    bar.set_style(
+        ProgressStyle::default_bar()
+            .template("{bar:40.cyan/blue} {pos}/{len} {msg}")
+            .expect("valid progress bar template"),
+    );
+
+    let mut records = reader.iter().map_err(|_| Error::Io)?;
+
+    while let Some(entry) = records.next() {
+        let entry = entry.map_err(|_| Error::Io)?;
+        bar.inc(1);
+        if let Some(pattern) = filter {
+            if !pattern.matches(&entry.path) {
+                continue;
+            }
+        }
+        bar.set_message(entry.path.clone());
+
+        let out_path = safe_join(out_dir, &entry.path)?;
+        if let Some(parent) = out_path.parent() {
+            fs::create_dir_all(parent).map_err(|_| Error::Io)?;
+        }
+
+        if entry.record_type == "DBR" {
+            let fields = records.read_record(&entry).map_err(|_| Error::Io)?;
+            fs::write(&out_path, format_fields(&fields)).map_err(|_| Error::Io)?;
+        } else {
+            let data = records.read_record_data(&entry).map_err(|_| Error::Io)?;
+            fs::write(&out_path, data).map_err(|_| Error::Io)?;
+        }
+    }
+
+    bar.finish_with_message("done");
+    Ok(())
+}
+
+/// Joins `record_path` onto `out_dir`, rejecting any path (absolute, or
+/// carrying a `..` component) that would otherwise let a malicious ARZ
+/// database write outside of `out_dir`.
+fn safe_join(out_dir: &Path, record_path: &str) -> Result<std::path::PathBuf, Error> {
+    use std::path::Component;
+
+    let relative = Path::new(record_path);
+    if relative
+        .components()
+        .any(|c| !matches!(c, Component::Normal(_)))
+    {
+        return Err(Error::UnsafeRecordPath(record_path.to_string()));
+    }
+
+    Ok(out_dir.join(relative))
+}
+
+/// Renders decoded DBR fields as `name=value1,value2` lines, one per field.
+fn format_fields(fields: &[Field]) -> String {
+    let mut text = String::new();
+    for field in fields {
+        let value = match &field.value {
+            FieldValue::Int(values) => values
+                .iter()
+                .map(i32::to_string)
+                .collect::<Vec<_>>()
+                .join(","),
+            FieldValue::Float(values) => values
+                .iter()
+                .map(f32::to_string)
+                .collect::<Vec<_>>()
+                .join(","),
+            FieldValue::Str(values) => values.join(","),
+            FieldValue::Bool(values) => values
+                .iter()
+                .map(bool::to_string)
+                .collect::<Vec<_>>()
+                .join(","),
+        };
+        text.push_str(&field.name);
+        text.push('=');
+        text.push_str(&value);
+        text.push('\n');
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn safe_join_accepts_plain_relative_paths() {
+        let joined = safe_join(Path::new("/out"), "records/sword.dbr").unwrap();
+        assert_eq!(joined, Path::new("/out/records/sword.dbr"));
+    }
+
+    #[test]
+    fn safe_join_rejects_parent_dir_components() {
+        match safe_join(Path::new("/out"), "../../etc/passwd") {
+            Err(Error::UnsafeRecordPath(path)) => assert_eq!(path, "../../etc/passwd"),
+            other => panic!("expected UnsafeRecordPath, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn safe_join_rejects_absolute_paths() {
+        match safe_join(Path::new("/out"), "/etc/passwd") {
+            Err(Error::UnsafeRecordPath(_)) => {}
+            other => panic!("expected UnsafeRecordPath, got {:?}", other),
+        }
+    }
+}
@@ -3,14 +3,29 @@
 use std::io;
 use std::io::prelude::*;
 use std::io::{Cursor, SeekFrom};
+use std::iter::FusedIterator;
 use std::result;
 
 use byteorder::{LittleEndian, ReadBytesExt};
+use flate2::read::ZlibDecoder;
+
+use crate::format::{Codec, Format};
+use crate::record;
+use crate::verify::{VerifyError, VerifyReport};
+
+/// Size in bytes of the fixed ARZ header that precedes the record data region.
+const HEADER_SIZE: u64 = 24;
 
 #[derive(Debug)]
 pub enum Error {
-    UnsupportedFormat,
+    UnsupportedFormat { id: u16, version: u16 },
     InvalidStringIndex(usize),
+    InvalidFieldType(u16),
+    CorruptRecord {
+        path: String,
+        expected: usize,
+        actual: usize,
+    },
     Io(io::Error),
 }
 
@@ -46,87 +61,482 @@ pub struct Entry {
 #[derive(Debug)]
 pub struct Reader<R: Read + Seek> {
     reader: R,
-    entries: Vec<Entry>,
+    format: Format,
+    record_table_start: u32,
+    record_table_size: u32,
+    record_table_entry_count: u32,
+    string_table_start: u32,
+    strings: Vec<String>,
 }
 
 impl<R: Read + Seek> Reader<R> {
-    pub fn entries(&self) -> &[Entry] {
-        &self.entries
+    /// The ARZ format variant detected from the file header.
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    /// Number of entries in the record table, as declared by the file header.
+    ///
+    /// Useful for sizing a progress bar before streaming through [`Reader::iter`].
+    pub fn record_table_entry_count(&self) -> u32 {
+        self.record_table_entry_count
     }
 
-    pub fn new(reader: R) -> Result<Reader<R>> {
-        let mut rdr = Reader {
+    /// Collects every entry in the record table into a `Vec`.
+    ///
+    /// This is a convenience wrapper around [`Reader::iter`] for callers that
+    /// want the whole table at once; for large ARZ databases prefer `iter`
+    /// to stream through entries with bounded memory.
+    pub fn entries(&mut self) -> Result<Vec<Entry>> {
+        self.iter()?.collect()
+    }
+
+    /// Returns an iterator that parses the record table one entry at a time,
+    /// starting from the beginning of the table, holding only the string
+    /// table in memory.
+    pub fn iter(&mut self) -> Result<RecordIter<'_, R>> {
+        Ok(RecordIter {
+            reader: &mut self.reader,
+            strings: &self.strings,
+            format: self.format,
+            remaining: self.record_table_entry_count,
+            pos: self.record_table_start as u64,
+        })
+    }
+
+    pub fn new(mut reader: R) -> Result<Reader<R>> {
+        reader.seek(SeekFrom::Start(0))?;
+        let header = read_header(&mut reader)?;
+        let format = Format::detect(header.id, header.version)?;
+        let strings = read_strings(&mut reader, &header, format)?;
+
+        Ok(Reader {
             reader,
-            entries: Vec::new(),
-        };
-        let entries = rdr.parse_header()?;
-        rdr.entries = entries;
-        Ok(rdr)
-    }
-
-    fn read_header(&mut self) -> Result<Header> {
-        let mut header_bytes = vec![0; 6 * 4];
-        self.reader.read_exact(&mut header_bytes)?;
-        let mut cursor = Cursor::new(header_bytes);
-        let mut header = Header::default();
-        header.id = cursor.read_u16::<LittleEndian>()?;
-        header.version = cursor.read_u16::<LittleEndian>()?;
-        if header.id != 0x02 || header.version != 0x03 {
-            return Err(Error::UnsupportedFormat);
+            format,
+            record_table_start: header.record_table_start,
+            record_table_size: header.record_table_size,
+            record_table_entry_count: header.record_table_entry_count,
+            string_table_start: header.string_table_start,
+            strings,
+        })
+    }
+
+    /// Reads and decompresses the record data for `entry`, using the codec
+    /// of the detected [`Format`].
+    ///
+    /// `entry.offset` is relative to the end of the 24-byte file header. The
+    /// decompressed payload is verified to be exactly `entry.uncompressed_size`
+    /// bytes long; a short or long result is reported as `Error::CorruptRecord`.
+    pub fn read_record_data(&mut self, entry: &Entry) -> Result<Vec<u8>> {
+        read_record_data_raw(&mut self.reader, self.format, entry)
+    }
+
+    /// Decompresses and decodes the typed key/value fields for `entry`.
+    pub fn read_record(&mut self, entry: &Entry) -> Result<Vec<record::Field>> {
+        let data = self.read_record_data(entry)?;
+        record::decode(&data, &self.strings)
+    }
+
+    /// Re-inflates every record and checksums the record-data region,
+    /// reporting mismatches instead of failing on the first one.
+    ///
+    /// This verifies (a) that each record decompresses to exactly its
+    /// declared `uncompressed_size`, and (b) that a CRC32 of the raw
+    /// record-data region matches the checksum stored in the database
+    /// footer, immediately after the record table.
+    pub fn verify(&mut self) -> Result<VerifyReport> {
+        let entries = self.entries()?;
+        let mut mismatches = Vec::new();
+
+        for entry in &entries {
+            match self.read_record_data(entry) {
+                Ok(_) => {}
+                Err(Error::CorruptRecord {
+                    expected, actual, ..
+                }) => {
+                    mismatches.push((entry.path.clone(), VerifyError::LengthMismatch { expected, actual }));
+                }
+                Err(err) => return Err(err),
+            }
+        }
+
+        let region_end = self.string_table_start.min(self.record_table_start) as u64;
+        let region_len = region_end.saturating_sub(HEADER_SIZE);
+        self.reader.seek(SeekFrom::Start(HEADER_SIZE))?;
+        let mut region = vec![0u8; region_len as usize];
+        self.reader.read_exact(&mut region)?;
+        let actual_checksum = crc32fast::hash(&region);
+
+        let footer_offset = self.record_table_start as u64 + self.record_table_size as u64;
+        self.reader.seek(SeekFrom::Start(footer_offset))?;
+        let expected_checksum = self.reader.read_u32::<LittleEndian>()?;
+
+        if actual_checksum != expected_checksum {
+            mismatches.push((
+                String::new(),
+                VerifyError::ChecksumMismatch {
+                    expected: expected_checksum,
+                    actual: actual_checksum,
+                },
+            ));
         }
-        header.record_table_start = cursor.read_u32::<LittleEndian>()?;
-        header.record_table_size = cursor.read_u32::<LittleEndian>()?;
-        header.record_table_entry_count = cursor.read_u32::<LittleEndian>()?;
-        header.string_table_start = cursor.read_u32::<LittleEndian>()?;
-        header.string_table_size = cursor.read_u32::<LittleEndian>()?;
-        Ok(header)
-    }
-
-    fn read_strings(&mut self, header: &Header) -> Result<Vec<String>> {
-        self.reader
-            .seek(SeekFrom::Start(header.string_table_start as u64))? as usize;
-        let strings_count = self.reader.read_u32::<LittleEndian>()? as usize;
-        let mut strings: Vec<String> = Vec::with_capacity(strings_count);
-        for _ in 0..strings_count {
-            let string_size = self.reader.read_u32::<LittleEndian>()? as usize;
+
+        Ok(VerifyReport {
+            records_checked: entries.len(),
+            mismatches,
+        })
+    }
+}
+
+fn read_header<R: Read + Seek>(reader: &mut R) -> Result<Header> {
+    let mut header_bytes = vec![0; 6 * 4];
+    reader.read_exact(&mut header_bytes)?;
+    let mut cursor = Cursor::new(header_bytes);
+    Ok(Header {
+        id: cursor.read_u16::<LittleEndian>()?,
+        version: cursor.read_u16::<LittleEndian>()?,
+        record_table_start: cursor.read_u32::<LittleEndian>()?,
+        record_table_size: cursor.read_u32::<LittleEndian>()?,
+        record_table_entry_count: cursor.read_u32::<LittleEndian>()?,
+        string_table_start: cursor.read_u32::<LittleEndian>()?,
+        string_table_size: cursor.read_u32::<LittleEndian>()?,
+    })
+}
+
+/// Reads and decompresses the record data for `entry` out of `reader`, using
+/// `format`'s codec. Shared by [`Reader::read_record_data`] and
+/// [`RecordIter::read_record_data`] so both can decode a record without
+/// requiring exclusive access to a whole `Reader`.
+fn read_record_data_raw<R: Read + Seek>(reader: &mut R, format: Format, entry: &Entry) -> Result<Vec<u8>> {
+    reader.seek(SeekFrom::Start(HEADER_SIZE + entry.offset as u64))?;
+    let mut compressed = vec![0u8; entry.compressed_size as usize];
+    reader.read_exact(&mut compressed)?;
+
+    let data = match format.codec() {
+        Codec::Zlib => {
+            let mut decoder = ZlibDecoder::new(compressed.as_slice());
+            let mut data = Vec::with_capacity(entry.uncompressed_size as usize);
+            if decoder.read_to_end(&mut data).is_err() {
+                return Err(Error::CorruptRecord {
+                    path: entry.path.clone(),
+                    expected: entry.uncompressed_size as usize,
+                    actual: data.len(),
+                });
+            }
+            data
+        }
+        Codec::Lz4 => lz4_flex::block::decompress(&compressed, entry.uncompressed_size as usize).map_err(|_| {
+            Error::CorruptRecord {
+                path: entry.path.clone(),
+                expected: entry.uncompressed_size as usize,
+                actual: 0,
+            }
+        })?,
+    };
+
+    if data.len() != entry.uncompressed_size as usize {
+        return Err(Error::CorruptRecord {
+            path: entry.path.clone(),
+            expected: entry.uncompressed_size as usize,
+            actual: data.len(),
+        });
+    }
+
+    Ok(data)
+}
+
+fn read_strings<R: Read + Seek>(
+    reader: &mut R,
+    header: &Header,
+    format: Format,
+) -> Result<Vec<String>> {
+    reader.seek(SeekFrom::Start(header.string_table_start as u64))?;
+    let strings_count = reader.read_u32::<LittleEndian>()? as usize;
+    let mut strings: Vec<String> = Vec::with_capacity(strings_count);
+    for _ in 0..strings_count {
+        let s = if format.has_length_prefixed_strings() {
+            let string_size = reader.read_u32::<LittleEndian>()? as usize;
             let mut string_bytes = vec![0; string_size];
-            self.reader.read_exact(&mut string_bytes)?;
-            let s = String::from_utf8_lossy(&mut string_bytes);
-            strings.push(s.to_string());
+            reader.read_exact(&mut string_bytes)?;
+            String::from_utf8_lossy(&string_bytes).to_string()
+        } else {
+            read_cstr(reader)?
+        };
+        strings.push(s);
+    }
+    Ok(strings)
+}
+
+/// Reads a null-terminated string, used by the `TitanQuest` string-table
+/// layout in place of the newer length-prefixed convention.
+fn read_cstr<R: Read>(reader: &mut R) -> Result<String> {
+    let mut bytes = Vec::new();
+    loop {
+        let byte = reader.read_u8()?;
+        if byte == 0 {
+            break;
+        }
+        bytes.push(byte);
+    }
+    Ok(String::from_utf8_lossy(&bytes).to_string())
+}
+
+/// Reads a single record-table entry from the reader's current seek position,
+/// resolving its path against `strings`. Older formats have no trailing
+/// `file_time` field; see [`Format::has_file_time`].
+fn read_entry<R: Read + Seek>(reader: &mut R, strings: &[String], format: Format) -> Result<Entry> {
+    let mut entry = Entry::default();
+    let path_index = reader.read_u32::<LittleEndian>()? as usize;
+    if path_index >= strings.len() {
+        return Err(Error::InvalidStringIndex(path_index));
+    }
+    entry.path = strings[path_index].to_string();
+    let record_type_len = reader.read_u32::<LittleEndian>()? as usize;
+    let mut record_type_bytes = vec![0; record_type_len];
+    reader.read_exact(&mut record_type_bytes)?;
+    entry.record_type = String::from_utf8_lossy(&record_type_bytes).to_string();
+    entry.offset = reader.read_u32::<LittleEndian>()?;
+    entry.compressed_size = reader.read_u32::<LittleEndian>()?;
+    entry.uncompressed_size = reader.read_u32::<LittleEndian>()?;
+    if format.has_file_time() {
+        entry.file_time = reader.read_u64::<LittleEndian>()?;
+    }
+    Ok(entry)
+}
+
+/// Lazily parses the record table one entry at a time from the current seek
+/// position, holding only the string table in memory. Constructed with
+/// [`Reader::iter`].
+pub struct RecordIter<'r, R: Read + Seek> {
+    reader: &'r mut R,
+    strings: &'r [String],
+    format: Format,
+    remaining: u32,
+    pos: u64,
+}
+
+impl<'r, R: Read + Seek> Iterator for RecordIter<'r, R> {
+    type Item = Result<Entry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        Some(self.read_next_entry())
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.remaining as usize;
+        (remaining, Some(remaining))
+    }
+}
+
+impl<'r, R: Read + Seek> RecordIter<'r, R> {
+    /// Seeks to the next unread record-table entry, reads it, and remembers
+    /// where the table resumes so `read_record_data`/`read_record` can seek
+    /// elsewhere to pull record data without losing our place.
+    fn read_next_entry(&mut self) -> Result<Entry> {
+        self.reader.seek(SeekFrom::Start(self.pos))?;
+        let entry = read_entry(self.reader, self.strings, self.format)?;
+        self.pos = self.reader.stream_position()?;
+        Ok(entry)
+    }
+
+    /// Reads and decompresses the record data for `entry`. Can be called
+    /// between calls to `next()` without disturbing iteration, since `next()`
+    /// seeks back to the saved record-table position before each entry.
+    pub fn read_record_data(&mut self, entry: &Entry) -> Result<Vec<u8>> {
+        read_record_data_raw(self.reader, self.format, entry)
+    }
+
+    /// Decompresses and decodes the typed key/value fields for `entry`.
+    pub fn read_record(&mut self, entry: &Entry) -> Result<Vec<record::Field>> {
+        let data = self.read_record_data(entry)?;
+        record::decode(&data, self.strings)
+    }
+}
+
+impl<'r, R: Read + Seek> FusedIterator for RecordIter<'r, R> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::ZlibEncoder;
+    use flate2::Compression;
+
+    fn zlib_compress(data: &[u8]) -> Vec<u8> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    /// Builds a well-formed GrimDawn-format (id 0x02, version 0x03) ARZ
+    /// buffer with one record per `(path, record_type, payload)` triple.
+    pub(super) fn build_arz(records: &[(&str, &str, &[u8])]) -> Vec<u8> {
+        let compressed: Vec<Vec<u8>> = records.iter().map(|(_, _, data)| zlib_compress(data)).collect();
+
+        let mut body = Vec::new();
+        let mut offsets = Vec::new();
+        for c in &compressed {
+            offsets.push(body.len() as u32);
+            body.extend_from_slice(c);
+        }
+
+        let mut string_table = Vec::new();
+        string_table.extend_from_slice(&(records.len() as u32).to_le_bytes());
+        for (path, _, _) in records {
+            string_table.extend_from_slice(&(path.len() as u32).to_le_bytes());
+            string_table.extend_from_slice(path.as_bytes());
+        }
+
+        let mut record_table = Vec::new();
+        for (i, (_, record_type, payload)) in records.iter().enumerate() {
+            record_table.extend_from_slice(&(i as u32).to_le_bytes());
+            record_table.extend_from_slice(&(record_type.len() as u32).to_le_bytes());
+            record_table.extend_from_slice(record_type.as_bytes());
+            record_table.extend_from_slice(&offsets[i].to_le_bytes());
+            record_table.extend_from_slice(&(compressed[i].len() as u32).to_le_bytes());
+            record_table.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+            record_table.extend_from_slice(&0u64.to_le_bytes());
         }
-        Ok(strings)
-    }
-
-    fn read_entries(&mut self, header: Header, strings: &[String]) -> Result<Vec<Entry>> {
-        self.reader
-            .seek(SeekFrom::Start(header.record_table_start as u64))? as usize;
-        let mut entries = Vec::with_capacity(header.record_table_entry_count as usize);
-        for _ in 0..header.record_table_entry_count {
-            let mut entry = Entry::default();
-            let path_index = self.reader.read_u32::<LittleEndian>()? as usize;
-            if path_index > strings.len() {
-                return Err(Error::InvalidStringIndex(path_index));
+
+        let string_table_start = HEADER_SIZE + body.len() as u64;
+        let record_table_start = string_table_start + string_table.len() as u64;
+        let record_table_size = record_table.len() as u64;
+        let checksum = crc32fast::hash(&body);
+
+        let mut file = Vec::new();
+        file.extend_from_slice(&2u16.to_le_bytes());
+        file.extend_from_slice(&3u16.to_le_bytes());
+        file.extend_from_slice(&(record_table_start as u32).to_le_bytes());
+        file.extend_from_slice(&(record_table_size as u32).to_le_bytes());
+        file.extend_from_slice(&(records.len() as u32).to_le_bytes());
+        file.extend_from_slice(&(string_table_start as u32).to_le_bytes());
+        file.extend_from_slice(&(string_table.len() as u32).to_le_bytes());
+        assert_eq!(file.len() as u64, HEADER_SIZE);
+
+        file.extend_from_slice(&body);
+        file.extend_from_slice(&string_table);
+        file.extend_from_slice(&record_table);
+        file.extend_from_slice(&checksum.to_le_bytes());
+        file
+    }
+
+    #[test]
+    fn entries_and_iter_agree() {
+        let data = build_arz(&[("a.dbr", "ITM", b"hello"), ("b.dbr", "ITM", b"world!")]);
+        let mut reader = Reader::new(Cursor::new(data)).unwrap();
+        let via_entries = reader.entries().unwrap();
+        let via_iter: Vec<Entry> = reader.iter().unwrap().collect::<Result<Vec<Entry>>>().unwrap();
+
+        assert_eq!(via_entries.len(), 2);
+        assert_eq!(via_entries.len(), via_iter.len());
+        for (a, b) in via_entries.iter().zip(via_iter.iter()) {
+            assert_eq!(a.path, b.path);
+            assert_eq!(a.record_type, b.record_type);
+            assert_eq!(a.offset, b.offset);
+            assert_eq!(a.compressed_size, b.compressed_size);
+            assert_eq!(a.uncompressed_size, b.uncompressed_size);
+        }
+        assert_eq!(via_entries[0].path, "a.dbr");
+        assert_eq!(via_entries[1].path, "b.dbr");
+    }
+
+    #[test]
+    fn iter_is_fused_once_exhausted() {
+        let data = build_arz(&[("a.dbr", "ITM", b"hi")]);
+        let mut reader = Reader::new(Cursor::new(data)).unwrap();
+        let mut it = reader.iter().unwrap();
+        assert!(it.next().unwrap().is_ok());
+        assert!(it.next().is_none());
+        assert!(it.next().is_none());
+    }
+
+    #[test]
+    fn read_record_data_roundtrips_through_zlib() {
+        let data = build_arz(&[("a.dbr", "ITM", b"payload-bytes")]);
+        let mut reader = Reader::new(Cursor::new(data)).unwrap();
+        let entries = reader.entries().unwrap();
+        let decoded = reader.read_record_data(&entries[0]).unwrap();
+        assert_eq!(decoded, b"payload-bytes");
+    }
+
+    #[test]
+    fn verify_reports_clean_database_as_ok() {
+        let data = build_arz(&[("a.dbr", "ITM", b"hello"), ("b.dbr", "ITM", b"world!")]);
+        let mut reader = Reader::new(Cursor::new(data)).unwrap();
+        let report = reader.verify().unwrap();
+        assert!(report.is_ok());
+        assert_eq!(report.records_checked, 2);
+        assert!(report.mismatches.is_empty());
+    }
+
+    #[test]
+    fn verify_detects_length_mismatch_without_aborting() {
+        let mut data = build_arz(&[("a.dbr", "ITM", b"hello"), ("b.dbr", "ITM", b"world!")]);
+        let header = read_header(&mut Cursor::new(data.clone())).unwrap();
+        // Layout of the first record-table entry: path_index(4) +
+        // record_type_len(4) + record_type("ITM", 3) + offset(4) +
+        // compressed_size(4) puts uncompressed_size at byte 19.
+        let uncompressed_size_offset = header.record_table_start as usize + 19;
+        data[uncompressed_size_offset..uncompressed_size_offset + 4].copy_from_slice(&4u32.to_le_bytes());
+
+        let mut reader = Reader::new(Cursor::new(data)).unwrap();
+        let report = reader.verify().unwrap();
+        assert!(!report.is_ok());
+        assert_eq!(report.records_checked, 2);
+        match &report.mismatches[0] {
+            (path, VerifyError::LengthMismatch { expected, actual }) => {
+                assert_eq!(path, "a.dbr");
+                assert_eq!(*expected, 4);
+                assert_eq!(*actual, 5);
+            }
+            other => panic!("expected LengthMismatch, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn verify_detects_checksum_mismatch() {
+        let mut data = build_arz(&[("a.dbr", "ITM", b"hello")]);
+        let last = data.len() - 1;
+        data[last] ^= 0xff;
+        let mut reader = Reader::new(Cursor::new(data)).unwrap();
+        let report = reader.verify().unwrap();
+        assert!(!report.is_ok());
+        assert_eq!(report.mismatches.len(), 1);
+        match &report.mismatches[0] {
+            (path, VerifyError::ChecksumMismatch { expected, actual }) => {
+                assert_eq!(path, "");
+                assert_ne!(expected, actual);
             }
-            entry.path = strings[path_index].to_string();
-            let record_type_len = self.reader.read_u32::<LittleEndian>()? as usize;
-            let mut record_type_bytes = vec![0; record_type_len];
-            self.reader.read_exact(&mut record_type_bytes)?;
-            entry.record_type = String::from_utf8_lossy(&record_type_bytes).to_string();
-            entry.offset = self.reader.read_u32::<LittleEndian>()?;
-            entry.compressed_size = self.reader.read_u32::<LittleEndian>()?;
-            entry.uncompressed_size = self.reader.read_u32::<LittleEndian>()?;
-            entry.file_time = self.reader.read_u64::<LittleEndian>()?;
-            entries.push(entry);
+            other => panic!("expected ChecksumMismatch, got {:?}", other),
         }
-        Ok(entries)
     }
 
-    fn parse_header(&mut self) -> Result<Vec<Entry>> {
-        self.reader.seek(SeekFrom::Start(0))?;
-        let header = self.read_header()?;
-        let strings = self.read_strings(&header)?;
-        let entries = self.read_entries(header, &strings)?;
+    #[test]
+    fn rejects_out_of_bounds_path_index_instead_of_panicking() {
+        let mut data = build_arz(&[("a.dbr", "ITM", b"hello")]);
+        let header = read_header(&mut Cursor::new(data.clone())).unwrap();
+        let record_table_start = header.record_table_start as usize;
+        // `path_index == strings.len()` is out of bounds (one string, valid
+        // index 0), but was previously accepted by an off-by-one check.
+        data[record_table_start..record_table_start + 4].copy_from_slice(&1u32.to_le_bytes());
+
+        let mut reader = Reader::new(Cursor::new(data)).unwrap();
+        match reader.entries() {
+            Err(Error::InvalidStringIndex(1)) => {}
+            other => panic!("expected InvalidStringIndex(1), got {:?}", other),
+        }
+    }
 
-        Ok(entries)
+    #[test]
+    fn rejects_header_with_unknown_id_version() {
+        let mut data = build_arz(&[("a.dbr", "ITM", b"hi")]);
+        data[2..4].copy_from_slice(&0xffu16.to_le_bytes());
+        match Reader::new(Cursor::new(data)) {
+            Err(Error::UnsupportedFormat { id: 0x02, version: 0xff }) => {}
+            other => panic!("expected UnsupportedFormat, got {:?}", other),
+        }
     }
 }
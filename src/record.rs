@@ -0,0 +1,168 @@
+//! Decodes a decompressed record payload (as produced by
+//! `Reader::read_record_data`) into typed key/value `Field`s.
+//!
+//! The payload is a flat sequence of `(type: u16, count: u16, name_index: u32,
+//! values: [u32; count])` entries, repeated until the buffer is exhausted.
+//! `name_index` and, for string-typed fields, each value are indices into the
+//! database's string table.
+
+use std::io::Cursor;
+
+use byteorder::{LittleEndian, ReadBytesExt};
+
+use crate::reader::{Error, Result};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FieldValue {
+    Int(Vec<i32>),
+    Float(Vec<f32>),
+    Str(Vec<String>),
+    Bool(Vec<bool>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Field {
+    pub name: String,
+    pub value: FieldValue,
+}
+
+fn lookup(strings: &[String], index: usize) -> Result<&str> {
+    strings
+        .get(index)
+        .map(String::as_str)
+        .ok_or(Error::InvalidStringIndex(index))
+}
+
+/// Decodes `data` as a flat sequence of `(type, count, name_index, values)`
+/// entries, resolving string-table indices against `strings`.
+pub fn decode(data: &[u8], strings: &[String]) -> Result<Vec<Field>> {
+    let mut cursor = Cursor::new(data);
+    let mut fields = Vec::new();
+
+    while (cursor.position() as usize) < data.len() {
+        let field_type = cursor.read_u16::<LittleEndian>()?;
+        let count = cursor.read_u16::<LittleEndian>()? as usize;
+        let name_index = cursor.read_u32::<LittleEndian>()? as usize;
+        let name = lookup(strings, name_index)?.to_string();
+
+        let mut raw = Vec::with_capacity(count);
+        for _ in 0..count {
+            raw.push(cursor.read_u32::<LittleEndian>()?);
+        }
+
+        let value = match field_type {
+            0 => FieldValue::Int(raw.iter().map(|&v| v as i32).collect()),
+            1 => FieldValue::Float(raw.iter().map(|&v| f32::from_bits(v)).collect()),
+            2 => {
+                let mut strs = Vec::with_capacity(raw.len());
+                for v in raw {
+                    strs.push(lookup(strings, v as usize)?.to_string());
+                }
+                FieldValue::Str(strs)
+            }
+            3 => FieldValue::Bool(raw.iter().map(|&v| v != 0).collect()),
+            other => return Err(Error::InvalidFieldType(other)),
+        };
+
+        fields.push(Field { name, value });
+    }
+
+    Ok(fields)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn field_bytes(field_type: u16, name_index: u32, values: &[u32]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&field_type.to_le_bytes());
+        bytes.extend_from_slice(&(values.len() as u16).to_le_bytes());
+        bytes.extend_from_slice(&name_index.to_le_bytes());
+        for v in values {
+            bytes.extend_from_slice(&v.to_le_bytes());
+        }
+        bytes
+    }
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn decodes_int_field() {
+        let strings = strings(&["level"]);
+        let data = field_bytes(0, 0, &[1, 2, u32::from_ne_bytes((-3i32).to_ne_bytes())]);
+        let fields = decode(&data, &strings).unwrap();
+        assert_eq!(fields.len(), 1);
+        assert_eq!(fields[0].name, "level");
+        assert_eq!(fields[0].value, FieldValue::Int(vec![1, 2, -3]));
+    }
+
+    #[test]
+    fn decodes_float_field() {
+        let strings = strings(&["scale"]);
+        let data = field_bytes(1, 0, &[1.5f32.to_bits(), (-2.25f32).to_bits()]);
+        let fields = decode(&data, &strings).unwrap();
+        assert_eq!(fields[0].value, FieldValue::Float(vec![1.5, -2.25]));
+    }
+
+    #[test]
+    fn decodes_string_field() {
+        let strings = strings(&["tag", "fire", "cold"]);
+        let data = field_bytes(2, 0, &[1, 2]);
+        let fields = decode(&data, &strings).unwrap();
+        assert_eq!(
+            fields[0].value,
+            FieldValue::Str(vec!["fire".to_string(), "cold".to_string()])
+        );
+    }
+
+    #[test]
+    fn decodes_bool_field() {
+        let strings = strings(&["enabled"]);
+        let data = field_bytes(3, 0, &[0, 1, 42]);
+        let fields = decode(&data, &strings).unwrap();
+        assert_eq!(fields[0].value, FieldValue::Bool(vec![false, true, true]));
+    }
+
+    #[test]
+    fn decodes_multiple_fields_until_buffer_exhausted() {
+        let strings = strings(&["a", "b"]);
+        let mut data = field_bytes(0, 0, &[1]);
+        data.extend(field_bytes(0, 1, &[2]));
+        let fields = decode(&data, &strings).unwrap();
+        assert_eq!(fields.len(), 2);
+        assert_eq!(fields[1].name, "b");
+    }
+
+    #[test]
+    fn rejects_out_of_range_name_index() {
+        let strings = strings(&["only"]);
+        let data = field_bytes(0, 5, &[1]);
+        match decode(&data, &strings) {
+            Err(Error::InvalidStringIndex(5)) => {}
+            other => panic!("expected InvalidStringIndex(5), got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rejects_out_of_range_string_value_index() {
+        let strings = strings(&["tag"]);
+        let data = field_bytes(2, 0, &[99]);
+        match decode(&data, &strings) {
+            Err(Error::InvalidStringIndex(99)) => {}
+            other => panic!("expected InvalidStringIndex(99), got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_field_type() {
+        let strings = strings(&["tag"]);
+        let data = field_bytes(9, 0, &[1]);
+        match decode(&data, &strings) {
+            Err(Error::InvalidFieldType(9)) => {}
+            other => panic!("expected InvalidFieldType(9), got {:?}", other),
+        }
+    }
+}
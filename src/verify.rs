@@ -0,0 +1,44 @@
+//! Integrity verification of record data and the database footer.
+//!
+//! An ARZ database is considered intact when every record inflates to
+//! exactly its declared `uncompressed_size` and a CRC32 of the record-data
+//! region (the bytes between the file header and the string/record tables)
+//! matches the checksum stored immediately after the record table. A pass
+//! collects every mismatch instead of stopping at the first one, so a single
+//! corrupt record doesn't prevent reporting the rest.
+
+#[derive(Debug)]
+pub enum VerifyError {
+    /// A record's inflated length didn't match its declared `uncompressed_size`.
+    LengthMismatch { expected: usize, actual: usize },
+    /// The CRC32 of the record-data region didn't match the stored footer value.
+    ChecksumMismatch { expected: u32, actual: u32 },
+}
+
+/// Result of a full [`crate::reader::Reader::verify`] pass.
+#[derive(Debug)]
+pub struct VerifyReport {
+    pub records_checked: usize,
+    /// `(entry path, error)` pairs; empty for a clean database. The footer
+    /// checksum mismatch, if any, is reported with an empty path.
+    pub mismatches: Vec<(String, VerifyError)>,
+}
+
+impl VerifyReport {
+    pub fn is_ok(&self) -> bool {
+        self.mismatches.is_empty()
+    }
+}
+
+impl std::fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VerifyError::LengthMismatch { expected, actual } => {
+                write!(f, "expected {} decompressed bytes, got {}", expected, actual)
+            }
+            VerifyError::ChecksumMismatch { expected, actual } => {
+                write!(f, "expected checksum {:#010x}, got {:#010x}", expected, actual)
+            }
+        }
+    }
+}